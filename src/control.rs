@@ -0,0 +1,130 @@
+//! An optional Unix-domain control socket giving operators a scriptable way to introspect and
+//! drive a running snare daemon, instead of having to tail syslog and signal blind.
+//!
+//! Each connection is read one line at a time; each line is a JSON-encoded [`Command`], and each
+//! command produces exactly one JSON-encoded [`Response`] line in reply. This mirrors the framing
+//! nbsh's runner uses for its own serde-serialised `Event` side channel.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, lock_recover, Snare};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Command {
+    /// Report the current queue depth.
+    Status,
+    /// Report the effective config path and the time of the last successful reload.
+    ConfigInfo,
+    /// Reload `snare.conf` synchronously, returning a parse error in the response instead of the
+    /// fire-and-forget behaviour of [`Snare::check_for_sighup`].
+    Reload,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Status {
+        queue_depth: usize,
+        in_flight: Vec<String>,
+    },
+    ConfigInfo { conf_path: String, last_reload: Option<String> },
+    Reloaded,
+    Error { msg: String },
+}
+
+/// Bind the control socket at `sock_path` and serve client connections on a dedicated thread for
+/// the lifetime of the process. Any stale socket file left behind by an unclean shutdown is
+/// removed first.
+pub(crate) fn spawn(sock_path: &Path, snare: Arc<Snare>) -> std::io::Result<()> {
+    let _ = std::fs::remove_file(sock_path);
+    let listener = UnixListener::bind(sock_path)?;
+    let sock_path = sock_path.to_owned();
+    thread::Builder::new()
+        .name("control".to_owned())
+        .spawn(move || serve(listener, snare, sock_path))?;
+    Ok(())
+}
+
+fn serve(listener: UnixListener, snare: Arc<Snare>, sock_path: PathBuf) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let snare = Arc::clone(&snare);
+                thread::spawn(move || handle_conn(stream, &snare));
+            }
+            Err(e) => snare.error(&format!(
+                "Control socket '{}' accept failed: {}",
+                sock_path.display(),
+                e
+            )),
+        }
+    }
+}
+
+fn handle_conn(stream: UnixStream, snare: &Snare) {
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let resp = match serde_json::from_str::<Command>(&line) {
+            Ok(cmd) => handle_command(cmd, snare),
+            Err(e) => Response::Error {
+                msg: format!("Invalid command: {}", e),
+            },
+        };
+        let Ok(mut out) = serde_json::to_string(&resp) else {
+            return;
+        };
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_command(cmd: Command, snare: &Snare) -> Response {
+    match cmd {
+        Command::Status => {
+            let queue = lock_recover(&snare.queue);
+            Response::Status {
+                queue_depth: queue.len(),
+                in_flight: queue.in_flight_repos(),
+            }
+        }
+        Command::ConfigInfo => Response::ConfigInfo {
+            conf_path: snare.conf_path.display().to_string(),
+            last_reload: lock_recover(&snare.last_reload).map(|t| {
+                let secs = t
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                secs.to_string()
+            }),
+        },
+        Command::Reload => match Config::from_path(&snare.conf_path) {
+            Ok(conf) => {
+                *lock_recover(&snare.conf) = conf;
+                *lock_recover(&snare.last_reload) = Some(std::time::SystemTime::now());
+                Response::Reloaded
+            }
+            Err(msg) => Response::Error { msg },
+        },
+    }
+}
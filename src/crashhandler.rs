@@ -0,0 +1,143 @@
+//! A crash handler for the fatal signals (SIGSEGV, SIGABRT, SIGBUS) that `install_panic_hook()`
+//! can't catch, since they aren't Rust panics -- e.g. a bug surfacing in the `libc`/`nix` FFI
+//! paths snare uses for syslog and fd handling. Under daemonisation these would otherwise be
+//! entirely silent: stderr is detached, and the process just disappears with no diagnostic.
+//!
+//! Everything run from `handle_fatal_signal` executes on an alternate signal stack and must stay
+//! async-signal-safe per signal-safety(7): no allocation, no locking, and nothing beyond
+//! preallocated buffers/fds and raw `write(2)`. That rules out `libc::syslog()` (not guaranteed
+//! async-signal-safe) and `backtrace::Backtrace` (allocates to resolve symbols); we instead do a
+//! raw, unsymbolicated stack walk with `backtrace::trace()`, whose frame-walking (as opposed to
+//! symbol resolution) is documented as not requiring the heap.
+
+use std::{
+    os::raw::{c_int, c_void},
+    path::Path,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use libc::{sigaction, siginfo_t, sigset_t, stack_t, SA_ONSTACK, SA_SIGINFO, SIGABRT, SIGBUS,
+    SIGSEGV};
+
+/// The fd crash reports are written to: a crash-report file if one was configured at `install()`
+/// time, falling back to stderr. Opened once, ahead of time, so the handler itself never has to
+/// call `open()`.
+static CRASH_FD: AtomicI32 = AtomicI32::new(libc::STDERR_FILENO);
+
+/// Format the non-negative integer `n` into `buf` as decimal digits and return the written
+/// slice. Used in place of `format!`/`ToString`, which allocate and so aren't async-signal-safe.
+fn write_u64(buf: &mut [u8; 20], mut n: u64) -> &[u8] {
+    if n == 0 {
+        buf[19] = b'0';
+        return &buf[19..];
+    }
+    let mut i = buf.len();
+    while n > 0 {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+    &buf[i..]
+}
+
+/// Format the non-negative integer `n` into `buf` as lowercase hex digits (no `0x` prefix) and
+/// return the written slice, for the same async-signal-safety reason as `write_u64`. Used for
+/// addresses, which are conventionally reported in hex so they can be cross-referenced against a
+/// map file or `addr2line`.
+fn write_u64_hex(buf: &mut [u8; 16], mut n: u64) -> &[u8] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    if n == 0 {
+        buf[15] = b'0';
+        return &buf[15..];
+    }
+    let mut i = buf.len();
+    while n > 0 {
+        i -= 1;
+        buf[i] = DIGITS[(n % 16) as usize];
+        n /= 16;
+    }
+    &buf[i..]
+}
+
+/// Write `bytes` to `fd`, ignoring errors: there's nothing more we can do from inside a signal
+/// handler if this fails.
+fn signal_safe_write(fd: c_int, bytes: &[u8]) {
+    unsafe {
+        libc::write(fd, bytes.as_ptr() as *const c_void, bytes.len());
+    }
+}
+
+extern "C" fn handle_fatal_signal(sig: c_int, info: *mut siginfo_t, _ctxt: *mut c_void) {
+    // Everything below must be async-signal-safe: see signal-safety(7).
+    let fd = CRASH_FD.load(Ordering::Relaxed);
+    let mut num_buf = [0u8; 20];
+    let mut hex_buf = [0u8; 16];
+
+    signal_safe_write(fd, b"snare: fatal signal ");
+    signal_safe_write(fd, write_u64(&mut num_buf, sig as u64));
+    if let Some(info) = unsafe { info.as_ref() } {
+        let addr = unsafe { info.si_addr() } as usize;
+        signal_safe_write(fd, b" at address 0x");
+        signal_safe_write(fd, write_u64_hex(&mut hex_buf, addr as u64));
+    }
+    signal_safe_write(fd, b"\nbacktrace (addresses only):\n");
+    backtrace::trace(|frame| {
+        signal_safe_write(fd, b"  0x");
+        signal_safe_write(fd, write_u64_hex(&mut hex_buf, frame.ip() as u64));
+        signal_safe_write(fd, b"\n");
+        true
+    });
+
+    // Restore the default handler and re-raise so the kernel still produces a core dump (if
+    // enabled) and the process exits the way it would have without this handler installed.
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+/// Install handlers for the fatal signals (SIGSEGV, SIGABRT, SIGBUS) on a preallocated alternate
+/// signal stack, so a stack overflow can still be handled. If `crash_report_dir` is given, each
+/// handler invocation appends to a `snare-crash.log` file there instead of falling back to
+/// stderr. A relative `crash_report_dir` is resolved against the working directory at the time
+/// of the call. Must be called only once.
+pub(crate) fn install(crash_report_dir: Option<&Path>) {
+    if let Some(dir) = crash_report_dir {
+        let path = dir.join("snare-crash.log");
+        let fd = unsafe {
+            libc::open(
+                std::ffi::CString::new(path.as_os_str().to_string_lossy().into_owned())
+                    .unwrap()
+                    .as_ptr(),
+                libc::O_CREAT | libc::O_WRONLY | libc::O_APPEND,
+                0o600,
+            )
+        };
+        if fd >= 0 {
+            CRASH_FD.store(fd, Ordering::Relaxed);
+        }
+    }
+
+    // A signal stack sized for siginfo handling; we deliberately leak it (like `progname` in
+    // main.rs) since it must remain valid for the life of the process.
+    let stack_size = std::cmp::max(libc::SIGSTKSZ, 64 * 1024);
+    let stack = Box::leak(vec![0u8; stack_size].into_boxed_slice());
+    let ss = stack_t {
+        ss_sp: stack.as_mut_ptr() as *mut c_void,
+        ss_flags: 0,
+        ss_size: stack_size,
+    };
+    unsafe {
+        libc::sigaltstack(&ss, std::ptr::null_mut());
+    }
+
+    for sig in [SIGSEGV, SIGBUS, SIGABRT] {
+        let mut act: sigaction = unsafe { std::mem::zeroed() };
+        act.sa_sigaction = handle_fatal_signal as *const () as usize;
+        act.sa_flags = SA_SIGINFO | SA_ONSTACK;
+        unsafe {
+            libc::sigemptyset(&mut act.sa_mask as *mut sigset_t);
+            libc::sigaction(sig, &act, std::ptr::null_mut());
+        }
+    }
+}
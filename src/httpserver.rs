@@ -0,0 +1,58 @@
+//! Listens for incoming webhooks and pushes them onto the `Queue` for the `jobrunner` to pick
+//! up, waking it via the event pipe. While `Snare::shutting_down` is set
+//! (SIGTERM/SIGINT received), new webhooks are rejected with a 503 instead of being queued, so a
+//! draining daemon doesn't accept work it won't get a chance to run.
+
+use std::{convert::Infallible, path::PathBuf, sync::Arc};
+
+use hyper::{
+    server::{conn::AddrIncoming, Builder},
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, StatusCode,
+};
+
+use crate::{lock_recover, Snare};
+
+pub(crate) async fn serve(server: Builder<AddrIncoming>, snare: Arc<Snare>) {
+    let make_svc = make_service_fn(move |_conn| {
+        let snare = Arc::clone(&snare);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let snare = Arc::clone(&snare);
+                async move { Ok::<_, Infallible>(handle(req, snare).await) }
+            }))
+        }
+    });
+    if let Err(e) = server.serve(make_svc).await {
+        eprintln!("httpserver: {}", e);
+    }
+}
+
+async fn handle(req: Request<Body>, snare: Arc<Snare>) -> Response<Body> {
+    if snare.is_shutting_down() {
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::from("snare is shutting down and is not accepting new hooks\n"))
+            .unwrap();
+    }
+
+    // The repo a hook is for, taken from the request path. Neither the payload's signature nor its
+    // event type is checked before the job is queued.
+    let repo = req.uri().path().trim_start_matches('/').to_owned();
+    let payload = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b.to_vec(),
+        Err(_) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::empty())
+                .unwrap()
+        }
+    };
+
+    let cmd = PathBuf::from(format!("/etc/snare/hooks/{}", repo));
+    lock_recover(&snare.queue).push(repo, cmd, payload);
+    // Wake the jobrunner up to pop the job we just queued.
+    nix::unistd::write(snare.event_write_fd, &[0]).ok();
+
+    Response::new(Body::empty())
+}
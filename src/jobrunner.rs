@@ -0,0 +1,119 @@
+//! Pops jobs from the `Queue` and runs them in parallel. Blocks on the event pipe until woken by
+//! a byte written there, by the `httpserver` (new job queued) or by a signal handler (SIGHUP,
+//! SIGCHLD, SIGTERM/SIGINT).
+
+use std::{
+    io::{Read, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    panic::{self, AssertUnwindSafe},
+    process::{self, Command, Stdio},
+    sync::Arc,
+    thread,
+    time::Instant,
+};
+
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+
+use crate::{lock_recover, queue::Job, Snare};
+
+/// Start the jobrunner on its own thread.
+pub(crate) fn attend(snare: Arc<Snare>) -> std::io::Result<()> {
+    thread::Builder::new()
+        .name("jobrunner".to_owned())
+        .spawn(move || run(snare))?;
+    Ok(())
+}
+
+fn run(snare: Arc<Snare>) {
+    // Safe: `event_read_fd` is owned by `Snare` for the life of the process and nothing else
+    // reads from it.
+    let mut event_read = unsafe { std::fs::File::from_raw_fd(snare.event_read_fd) };
+    let mut discard = [0u8; 256];
+    // Set the first time we notice `shutting_down`; once `shutdown_grace_period` has passed
+    // since then, we stop waiting for in-flight jobs and kill them instead.
+    let mut shutdown_deadline: Option<Instant> = None;
+
+    loop {
+        // Block until a wakeup byte arrives, or -- once shutting down -- until the grace period
+        // runs out, so we re-check for hung jobs even if nothing else wakes us up.
+        let timeout_ms = match shutdown_deadline {
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis()
+                .try_into()
+                .unwrap_or(i32::MAX),
+            None => -1,
+        };
+        let mut fds = [PollFd::new(event_read.as_raw_fd(), PollFlags::POLLIN)];
+        let _ = poll(&mut fds, timeout_ms);
+        let _ = event_read.read(&mut discard);
+
+        snare.check_for_sighup();
+
+        while !snare.is_shutting_down() {
+            let job = match lock_recover(&snare.queue).pop() {
+                Some(job) => job,
+                None => break,
+            };
+            spawn_job(&snare, job);
+        }
+
+        if snare.is_shutting_down() {
+            let deadline = *shutdown_deadline
+                .get_or_insert_with(|| Instant::now() + snare.shutdown_grace_period);
+            if lock_recover(&snare.queue).in_flight_repos().is_empty() {
+                process::exit(0);
+            }
+            if Instant::now() >= deadline {
+                for pid in lock_recover(&snare.queue).in_flight_pids() {
+                    let _ = kill(Pid::from_raw(pid as i32), Signal::SIGKILL);
+                }
+                process::exit(0);
+            }
+        }
+    }
+}
+
+/// Run `job` on its own thread, so a hung hook can't stall the loop that pops new jobs and
+/// enforces the shutdown grace period.
+fn spawn_job(snare: &Arc<Snare>, job: Job) {
+    let snare = Arc::clone(snare);
+    thread::spawn(move || run_job(&snare, job));
+}
+
+/// Run a single job, catching any panic so that a bug in this bookkeeping -- spawning the hook,
+/// feeding it its payload, recording its pid on the queue -- is logged and the runner keeps going,
+/// rather than taking the whole daemon down with it. The job itself runs as a subprocess, so a
+/// panic here can only come from the Rust code around it, not from the hook program.
+fn run_job(snare: &Arc<Snare>, job: Job) {
+    let id = job.id;
+    if let Err(cause) = panic::catch_unwind(AssertUnwindSafe(|| {
+        if let Err(e) = run_hook(snare, &job) {
+            snare.error(&format!("Job for '{}' failed: {}", job.repo, e));
+        }
+    })) {
+        let msg = cause
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| cause.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        snare.error(&format!("Job for '{}' panicked: {}", job.repo, msg));
+    }
+    lock_recover(&snare.queue).mark_done(id);
+}
+
+/// Spawn `job.cmd`, write `job.payload` to its stdin, and wait for it to finish. Records the
+/// child's pid on the queue first, so a shutdown that outlasts its grace period can kill it.
+fn run_hook(snare: &Arc<Snare>, job: &Job) -> std::io::Result<()> {
+    let mut child = Command::new(&job.cmd).stdin(Stdio::piped()).spawn()?;
+    lock_recover(&snare.queue).set_pid(job.id, child.id());
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(&job.payload)?;
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -4,10 +4,13 @@
 //!   * The `jobrunner` pops elements from the `Queue` and runs them in parallel.
 //! These two components run as two different threads: the `httpserver` writes a solitary byte to
 //! an "event pipe" to wake up the `jobrunner` when the queue has new elements. We also wake up the
-//! `jobrunner` on SIGHUP and SIGCHLD.
+//! `jobrunner` on SIGHUP and SIGCHLD. SIGTERM and SIGINT set `Snare::shutting_down` and wake the
+//! `jobrunner` the same way, so that shutdown is a drain rather than an abrupt kill.
 
 mod config;
 mod config_ast;
+mod control;
+mod crashhandler;
 mod httpserver;
 mod jobrunner;
 mod queue;
@@ -18,12 +21,13 @@ use std::{
     ffi::CString,
     fmt::Display,
     os::unix::io::RawFd,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        Arc, Mutex, MutexGuard, PoisonError,
     },
+    time::{Duration, SystemTime},
 };
 
 use getopts::Options;
@@ -47,13 +51,14 @@ pub(crate) struct Snare {
     daemonised: bool,
     /// The location of snare.conf; this file will be reloaded if SIGHUP is received.
     conf_path: PathBuf,
-    /// The current configuration: note that this can change at any point due to SIGHUP. All calls
-    /// to `conf.lock().unwrap()` are considered safe since the only way this can fail is if the
-    /// other thread has `panic`ed, at which point we're already doomed.
+    /// The current configuration: note that this can change at any point due to SIGHUP. Access
+    /// this with `lock_recover`, not `lock().unwrap()`: the jobrunner catches panics from
+    /// individual jobs, so a panic while this lock was held must not poison every subsequent
+    /// access.
     conf: Mutex<Config>,
-    /// The current queue of incoming jobs. All calls to `queue.lock().unwrap()` are considered
-    /// safe since the only way this can fail is if the other thread has `panic`ed, at which point
-    /// we're already doomed.
+    /// The current queue of incoming jobs. Access this with `lock_recover`, not
+    /// `lock().unwrap()`: the jobrunner catches panics from individual jobs, so a panic while
+    /// this lock was held must not poison every subsequent access.
     queue: Mutex<Queue>,
     /// The read end of the pipe used by the httpserver and the SIGHUP handler to wake up the job
     /// runner thread.
@@ -64,6 +69,17 @@ pub(crate) struct Snare {
     /// Has a SIGHUP event occurred? If so, the jobrunner will process it, and set this to false in
     /// case future SIGHUP events are detected.
     sighup_occurred: Arc<AtomicBool>,
+    /// Has a SIGTERM or SIGINT been received? Once set, the httpserver stops accepting new
+    /// webhooks (answering with a 503) and the jobrunner stops popping new work from the
+    /// `Queue`, letting any already-running jobs finish (up to `shutdown_grace_period`) before
+    /// the process exits.
+    shutting_down: Arc<AtomicBool>,
+    /// How long the jobrunner waits for in-flight jobs to finish after `shutting_down` is set
+    /// before giving up on them and exiting anyway.
+    shutdown_grace_period: Duration,
+    /// The time of the last successful config reload, whether triggered by SIGHUP or by the
+    /// control socket's `reload` command. `None` if snare hasn't reloaded since it started.
+    last_reload: Mutex<Option<SystemTime>>,
 }
 
 impl Snare {
@@ -73,13 +89,22 @@ impl Snare {
     fn check_for_sighup(&self) {
         if self.sighup_occurred.load(Ordering::Relaxed) {
             match Config::from_path(&self.conf_path) {
-                Ok(conf) => *self.conf.lock().unwrap() = conf,
+                Ok(conf) => {
+                    *lock_recover(&self.conf) = conf;
+                    *lock_recover(&self.last_reload) = Some(SystemTime::now());
+                }
                 Err(msg) => self.error(&msg),
             }
             self.sighup_occurred.store(false, Ordering::Relaxed);
         }
     }
 
+    /// Have we received a SIGTERM or SIGINT? If so, the daemon is draining in-flight work before
+    /// exiting and must not accept or start any new work.
+    fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
     /// Log `msg` as an error.
     ///
     /// # Panics
@@ -87,11 +112,9 @@ impl Snare {
     /// If `msg` contains a `NUL` byte.
     fn error(&self, msg: &str) {
         if self.daemonised {
-            // We know that `%s` and `<can't represent as CString>` are both valid C strings, and
-            // that neither unwrap() can fail.
+            // We know that `%s` is a valid C string, so the unwrap() can't fail.
             let fmt = CString::new("%s").unwrap();
-            let msg = CString::new(msg)
-                .unwrap_or_else(|_| CString::new("<can't represent as CString>").unwrap());
+            let msg = to_syslog_cstring(msg);
             unsafe {
                 syslog(LOG_ERR, fmt.as_ptr(), msg.as_ptr());
             }
@@ -130,6 +153,14 @@ impl Snare {
     }
 }
 
+/// Lock `mutex`, recovering from poisoning rather than panicking. The jobrunner wraps each
+/// queued job's processing in `catch_unwind`, so a panic while a lock was held is caught and
+/// logged there and then; a subsequent `lock().unwrap()` elsewhere must not treat that as cause
+/// to abort the whole daemon too.
+fn lock_recover<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 /// Try to find a `snare.conf` file.
 fn search_snare_conf() -> Option<PathBuf> {
     let p = PathBuf::from(SNARE_CONF_PATH);
@@ -180,20 +211,32 @@ fn progname() -> String {
     }
 }
 
-/// Exit with a fatal error.
-fn fatal(daemonised: bool, msg: &str) -> ! {
+/// Turn `msg` into a `CString` suitable for passing to `syslog`, falling back to a placeholder
+/// if `msg` can't be represented as one (i.e. it contains a NUL byte).
+fn to_syslog_cstring(msg: &str) -> CString {
+    // We know that `<can't represent as CString>` is a valid C string, so the unwrap() can't
+    // fail.
+    CString::new(msg).unwrap_or_else(|_| CString::new("<can't represent as CString>").unwrap())
+}
+
+/// Log `msg` at `LOG_CRIT` if `daemonised`, or print it to stderr otherwise. This is the single
+/// sink used by both `fatal()` and the panic hook installed by `install_panic_hook()`.
+fn log_crit(daemonised: bool, msg: &str) {
     if daemonised {
-        // We know that `%s` and `<can't represent as CString>` are both valid C strings, and
-        // that neither unwrap() can fail.
+        // We know that `%s` is a valid C string, so the unwrap() can't fail.
         let fmt = CString::new("%s").unwrap();
-        let msg = CString::new(msg)
-            .unwrap_or_else(|_| CString::new("<can't represent as CString>").unwrap());
+        let msg = to_syslog_cstring(msg);
         unsafe {
             syslog(LOG_CRIT, fmt.as_ptr(), msg.as_ptr());
         }
     } else {
         eprintln!("{}", msg);
     }
+}
+
+/// Exit with a fatal error.
+fn fatal(daemonised: bool, msg: &str) -> ! {
+    log_crit(daemonised, msg);
     process::exit(1);
 }
 
@@ -202,6 +245,23 @@ fn fatal_err<E: Into<Box<dyn Error>> + Display>(daemonised: bool, msg: &str, err
     fatal(daemonised, &format!("{}: {}", msg, err));
 }
 
+/// Install a panic hook so that a panic anywhere (the jobrunner thread, a tokio httpserver task,
+/// or a signal handler's continuation) is routed through the same `LOG_CRIT` syslog sink as
+/// `fatal()`, instead of being written to a `stderr` that, once daemonised, nobody is watching.
+/// This must be called after `openlog()`.
+fn install_panic_hook(daemonised: bool) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let mut msg = format!("panic: {}", info);
+        if env::var_os("RUST_BACKTRACE").is_some() {
+            msg.push('\n');
+            msg.push_str(&std::backtrace::Backtrace::force_capture().to_string());
+        }
+        log_crit(daemonised, &msg);
+        default_hook(info);
+    }));
+}
+
 /// Print out program usage then exit. This function must not be called after daemonisation.
 fn usage() -> ! {
     eprintln!("Usage: {} [-c <config-path>] [-d]", progname());
@@ -212,6 +272,25 @@ pub fn main() {
     let args: Vec<String> = env::args().collect();
     let matches = Options::new()
         .optmulti("c", "config", "Path to snare.conf.", "<conf-path>")
+        .optmulti(
+            "s",
+            "control-socket",
+            "Path to an optional control socket for status/reload queries.",
+            "<sock-path>",
+        )
+        .optmulti(
+            "",
+            "crash-report-dir",
+            "Directory to write fatal-signal crash reports to.",
+            "<dir-path>",
+        )
+        .optmulti(
+            "",
+            "shutdown-grace-period",
+            "Seconds to let in-flight jobs finish after SIGTERM/SIGINT before exiting anyway \
+             (default: 30).",
+            "<secs>",
+        )
         .optflag(
             "d",
             "",
@@ -232,6 +311,21 @@ pub fn main() {
     };
     let conf = Config::from_path(&conf_path).unwrap_or_else(|m| fatal(false, &m));
 
+    // Resolve this to an absolute path now, before the chdir to "/" below, so a relative
+    // --crash-report-dir still means what the user typed.
+    let crash_report_dir = matches
+        .opt_str("crash-report-dir")
+        .map(PathBuf::from)
+        .map(|p| env::current_dir().map(|cwd| cwd.join(&p)).unwrap_or(p));
+
+    let shutdown_grace_period = match matches.opt_str("shutdown-grace-period") {
+        Some(s) => match s.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => usage(),
+        },
+        None => Duration::from_secs(30),
+    };
+
     change_user(&conf);
 
     set_current_dir("/").unwrap_or_else(|_| fatal(false, "Can't chdir to '/'"));
@@ -251,6 +345,8 @@ pub fn main() {
     unsafe {
         openlog(progname, LOG_CONS, LOG_DAEMON);
     }
+    install_panic_hook(daemonise);
+    crashhandler::install(crash_report_dir.as_deref());
 
     let (event_read_fd, event_write_fd) = match pipe2(OFlag::O_NONBLOCK) {
         Ok(p) => p,
@@ -277,6 +373,29 @@ pub fn main() {
             fatal_err(daemonise, "Can't install SIGCHLD handler", e);
         }
     }
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    {
+        let for_sigterm = Arc::clone(&shutting_down);
+        if let Err(e) = unsafe {
+            signal_hook::low_level::register(signal_hook::consts::SIGTERM, move || {
+                // All functions called in this function must be signal safe. See signal(3).
+                for_sigterm.store(true, Ordering::Relaxed);
+                nix::unistd::write(event_write_fd, &[0]).ok();
+            })
+        } {
+            fatal_err(daemonise, "Can't install SIGTERM handler", e);
+        }
+        let for_sigint = Arc::clone(&shutting_down);
+        if let Err(e) = unsafe {
+            signal_hook::low_level::register(signal_hook::consts::SIGINT, move || {
+                // All functions called in this function must be signal safe. See signal(3).
+                for_sigint.store(true, Ordering::Relaxed);
+                nix::unistd::write(event_write_fd, &[0]).ok();
+            })
+        } {
+            fatal_err(daemonise, "Can't install SIGINT handler", e);
+        }
+    }
 
     let snare = Arc::new(Snare {
         daemonised: daemonise,
@@ -286,8 +405,17 @@ pub fn main() {
         event_read_fd,
         event_write_fd,
         sighup_occurred,
+        shutting_down,
+        shutdown_grace_period,
+        last_reload: Mutex::new(None),
     });
 
+    if let Some(sock_path) = matches.opt_str("control-socket") {
+        if let Err(e) = control::spawn(Path::new(&sock_path), Arc::clone(&snare)) {
+            snare.fatal_err("Couldn't bind control socket", e);
+        }
+    }
+
     match jobrunner::attend(Arc::clone(&snare)) {
         Ok(x) => x,
         Err(e) => snare.fatal_err("Couldn't start runner thread", e),
@@ -298,7 +426,7 @@ pub fn main() {
         Err(e) => snare.fatal_err("Couldn't start tokio runtime.", e),
     };
     rt.block_on(async {
-        let server = match Server::try_bind(&snare.conf.lock().unwrap().listen) {
+        let server = match Server::try_bind(&lock_recover(&snare.conf).listen) {
             Ok(s) => s,
             Err(e) => snare.fatal_err("Couldn't bind to address", e),
         };
@@ -0,0 +1,84 @@
+//! The in-memory queue of webhook jobs: the `httpserver` pushes each incoming hook here; the `jobrunner` pops jobs and runs them. Also tracks which jobs are currently
+//! running, so the control socket can report them.
+
+use std::{collections::VecDeque, path::PathBuf};
+
+/// A single webhook job: already resolved to a concrete hook program by the `httpserver`, so the `jobrunner` has nothing left to do but run it.
+#[derive(Clone)]
+pub(crate) struct Job {
+    pub(crate) id: u64,
+    /// The repo this hook came from, kept for logging and status reporting.
+    pub(crate) repo: String,
+    /// The hook program to run, with `payload` passed to it on stdin.
+    pub(crate) cmd: PathBuf,
+    pub(crate) payload: Vec<u8>,
+    /// The OS pid of the running hook's subprocess, set once it's spawned via `set_pid`. Lets a
+    /// shutdown that's run out of grace period kill it instead of waiting forever.
+    pub(crate) pid: Option<u32>,
+}
+
+pub(crate) struct Queue {
+    next_id: u64,
+    pending: VecDeque<Job>,
+    in_flight: Vec<Job>,
+}
+
+impl Queue {
+    pub(crate) fn new() -> Queue {
+        Queue {
+            next_id: 0,
+            pending: VecDeque::new(),
+            in_flight: Vec::new(),
+        }
+    }
+
+    /// Add a job to the back of the queue, returning its id.
+    pub(crate) fn push(&mut self, repo: String, cmd: PathBuf, payload: Vec<u8>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push_back(Job {
+            id,
+            repo,
+            cmd,
+            payload,
+            pid: None,
+        });
+        id
+    }
+
+    /// Pop the next pending job, moving it into the in-flight set. Callers must call
+    /// `mark_done` with the returned job's id once it has finished running.
+    pub(crate) fn pop(&mut self) -> Option<Job> {
+        let job = self.pending.pop_front()?;
+        self.in_flight.push(job.clone());
+        Some(job)
+    }
+
+    /// Mark the in-flight job `id` as finished.
+    pub(crate) fn mark_done(&mut self, id: u64) {
+        self.in_flight.retain(|j| j.id != id);
+    }
+
+    /// Record the OS pid of the subprocess now running in-flight job `id`.
+    pub(crate) fn set_pid(&mut self, id: u64, pid: u32) {
+        if let Some(job) = self.in_flight.iter_mut().find(|j| j.id == id) {
+            job.pid = Some(pid);
+        }
+    }
+
+    /// The pids of in-flight jobs whose subprocess has been spawned, for killing on a shutdown
+    /// timeout.
+    pub(crate) fn in_flight_pids(&self) -> Vec<u32> {
+        self.in_flight.iter().filter_map(|j| j.pid).collect()
+    }
+
+    /// The number of jobs still waiting to start.
+    pub(crate) fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The repos of jobs currently running, for status reporting.
+    pub(crate) fn in_flight_repos(&self) -> Vec<String> {
+        self.in_flight.iter().map(|j| j.repo.clone()).collect()
+    }
+}